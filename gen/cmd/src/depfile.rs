@@ -0,0 +1,64 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Accumulates the targets and prerequisites of a build so that they can
+/// be written out as a Makefile-style `.d` depfile, allowing build systems
+/// such as cargo, ninja or bazel to know when generated files need to be
+/// regenerated.
+#[derive(Debug, Default)]
+pub(crate) struct Depfile {
+    targets: Vec<PathBuf>,
+    dependencies: Vec<PathBuf>,
+}
+
+impl Depfile {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a file which is written as output of this run.
+    pub(crate) fn add_target(&mut self, target: impl Into<PathBuf>) {
+        self.targets.push(target.into());
+    }
+
+    /// Record a file which was read as an input to this run (e.g. a C++
+    /// header opened while resolving an `include_cpp!` macro).
+    pub(crate) fn add_dependency(&mut self, dependency: impl Into<PathBuf>) {
+        self.dependencies.push(dependency.into());
+    }
+
+    /// Write this depfile to `path` in the format understood by Make and
+    /// Ninja: a single rule of the form `target1 target2: \` followed by
+    /// one dependency per continuation line.
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        let targets: Vec<String> = self.targets.iter().map(|p| escape_path(p)).collect();
+        write!(f, "{}:", targets.join(" "))?;
+        for dep in &self.dependencies {
+            write!(f, " \\\n  {}", escape_path(dep))?;
+        }
+        writeln!(f)?;
+        Ok(())
+    }
+}
+
+/// Escapes a path for use in a Makefile depfile, backslash-escaping spaces
+/// as Make requires.
+fn escape_path(path: &Path) -> String {
+    path.to_string_lossy().replace(' ', "\\ ")
+}
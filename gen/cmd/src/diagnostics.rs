@@ -0,0 +1,64 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured error reporting for autocxx-gen, modelled on rustc's
+//! `--message-format json` diagnostic schema so that editors and build
+//! drivers which already know how to parse cargo/rustc JSON diagnostics
+//! can consume autocxx's errors too.
+
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub(crate) struct Span {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) line: usize,
+    pub(crate) col: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Diagnostic {
+    pub(crate) level: String,
+    pub(crate) message: String,
+    pub(crate) file: String,
+    pub(crate) spans: Vec<Span>,
+    pub(crate) rendered: String,
+}
+
+/// Report `err` and exit the process with a non-zero status, but without
+/// unwinding a panic. In human mode this prints much the same message that
+/// `.expect()` used to produce; in JSON mode it prints a single-line
+/// `Diagnostic` to stdout so that tooling can parse it.
+pub(crate) fn fail(file: &str, err: impl std::fmt::Display, json: bool) -> ! {
+    let message = err.to_string();
+    if json {
+        let diagnostic = Diagnostic {
+            level: "error".to_string(),
+            message: message.clone(),
+            file: file.to_string(),
+            // autocxx-engine does not currently expose the spans of the
+            // errors it returns, so we report the failure without them;
+            // once it does, they belong here.
+            spans: Vec::new(),
+            rendered: format!("error: {}\n", message),
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&diagnostic).expect("Unable to serialize diagnostic")
+        );
+    } else {
+        eprintln!("error: {}", message);
+    }
+    std::process::exit(1);
+}
@@ -12,14 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use autocxx_engine::parse_file;
+mod depfile;
+mod diagnostics;
+mod rustfmt;
+mod snapshot_test;
+mod toolchain;
+
+use autocxx_engine::{parse_file, RebuildDependencyRecorder};
 use clap::{crate_authors, crate_version, App, Arg, SubCommand};
+use depfile::Depfile;
 use indoc::indoc;
 use proc_macro2::TokenStream;
 use quote::ToTokens;
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::rc::Rc;
+use toolchain::Toolchain;
+
+/// Feeds the headers that autocxx-engine reports opening while resolving
+/// `include_cpp!` back into our `Depfile`.
+#[derive(Debug)]
+struct DepfileRecorder(Rc<RefCell<Depfile>>);
+
+impl RebuildDependencyRecorder for DepfileRecorder {
+    fn record_header_file_dependency(&self, filename: &str) {
+        self.0.borrow_mut().add_dependency(filename);
+    }
+}
 
 fn main() {
     let matches = App::new("autocxx-gen")
@@ -37,8 +58,7 @@ fn main() {
         "})
         .arg(
             Arg::with_name("INPUT")
-                .help("Sets the input .rs file to use")
-                .required(true)
+                .help("Sets the input .rs file to use (not required for the test subcommand)")
                 .index(1),
         )
         .arg(
@@ -46,9 +66,8 @@ fn main() {
                 .short("o")
                 .long("outdir")
                 .value_name("PATH")
-                .help("output directory path")
-                .takes_value(true)
-                .required(true),
+                .help("output directory path (not required for the test subcommand)")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("inc")
@@ -57,6 +76,34 @@ fn main() {
                 .help("include path")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("depfile")
+                .long("depfile")
+                .value_name("PATH")
+                .help("Makefile-style depfile to write, listing the C++ headers which were read")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-format")
+                .long("message-format")
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .help("Error format to use; 'json' emits a single rustc-style diagnostic object instead of panicking")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rustfmt")
+                .long("rustfmt")
+                .value_name("PATH")
+                .help("Path to the rustfmt binary to use to format gen-rs output (defaults to $RUSTFMT, then rustfmt on PATH)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("emit-unformatted")
+                .long("emit-unformatted")
+                .help("Skip running rustfmt over gen-rs output"),
+        )
         .subcommand(
             SubCommand::with_name("gen-cpp")
                 .help("Generate C++ .cpp and .h files. Normal mode of operation.")
@@ -85,62 +132,240 @@ fn main() {
                 ),
         )
         .subcommand(SubCommand::with_name("gen-rs").help("Generate expanded Rust file."))
+        .subcommand(
+            SubCommand::with_name("build")
+                .help("Generate C++ and compile it into a static library.")
+                .arg(
+                    Arg::with_name("pattern")
+                        .long("pattern")
+                        .value_name("PATTERN")
+                        .help(".h and .cpp output pattern")
+                        .default_value("gen")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cpp-extension")
+                        .long("cpp-extension")
+                        .value_name("EXTENSION")
+                        .default_value("cc")
+                        .help("file extension for the generated C++ implementation files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("generate-exact")
+                        .long("generate-exact")
+                        .value_name("NUM")
+                        .help("always generate this number of .cc and .h files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("std")
+                        .long("std")
+                        .value_name("STD")
+                        .default_value("c++14")
+                        .help("C++ standard to compile against")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("out-name")
+                        .long("out-name")
+                        .value_name("NAME")
+                        .default_value("autocxxgen")
+                        .help("base name of the output static library, e.g. NAME in libNAME.a")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .help("Run the golden-file regression suite over a directory of .rs fixtures.")
+                .arg(
+                    Arg::with_name("fixtures-dir")
+                        .long("fixtures-dir")
+                        .value_name("PATH")
+                        .help("Directory containing NAME.rs fixtures and NAME.expected/ output dirs")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("bless")
+                        .long("bless")
+                        .help("Overwrite NAME.expected/ with freshly generated output"),
+                )
+                .arg(
+                    Arg::with_name("mode")
+                        .long("mode")
+                        .value_name("MODE")
+                        .possible_values(&["all", "gen-cpp", "gen-rs"])
+                        .default_value("all")
+                        .help("Restrict testing to one generation mode")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
-    let mut parsed_file = parse_file(matches.value_of("INPUT").unwrap())
-        .expect("Unable to parse Rust file and interpret autocxx macro");
+    if let Some(matches) = matches.subcommand_matches("test") {
+        let config = snapshot_test::Config {
+            fixtures_dir: PathBuf::from(matches.value_of("fixtures-dir").unwrap()),
+            bless: matches.is_present("bless"),
+            mode: snapshot_test::ModeFilter::parse(matches.value_of("mode").unwrap_or("all")),
+        };
+        if !snapshot_test::run(&config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let json_diagnostics = matches.value_of("message-format") == Some("json");
+    let input = matches.value_of("INPUT").unwrap_or_else(|| {
+        eprintln!("error: the INPUT file is required outside of the test subcommand");
+        std::process::exit(1);
+    });
+    let mut parsed_file =
+        parse_file(input).unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
     let incs = matches.value_of("inc").unwrap_or("");
-    // TODO - in future, we should provide an option to write a .d file here
-    // by passing a callback into the dep_recorder parameter here.
+    let depfile: Option<(Rc<RefCell<Depfile>>, PathBuf)> = matches
+        .value_of("depfile")
+        .map(|path| (Rc::new(RefCell::new(Depfile::new())), PathBuf::from(path)));
+    if let Some((depfile, _)) = &depfile {
+        depfile.borrow_mut().add_dependency(input);
+    }
+    let dep_recorder: Option<Box<dyn RebuildDependencyRecorder>> =
+        depfile
+            .as_ref()
+            .map(|(depfile, _)| -> Box<dyn RebuildDependencyRecorder> {
+                Box::new(DepfileRecorder(Rc::clone(depfile)))
+            });
     parsed_file
-        .resolve_all(incs, None)
-        .expect("Unable to resolve macro");
-    let outdir: PathBuf = matches.value_of_os("outdir").unwrap().into();
+        .resolve_all(incs, dep_recorder)
+        .unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
+    let outdir: PathBuf = matches
+        .value_of_os("outdir")
+        .unwrap_or_else(|| {
+            eprintln!("error: --outdir is required outside of the test subcommand");
+            std::process::exit(1);
+        })
+        .into();
     if let Some(matches) = matches.subcommand_matches("gen-cpp") {
-        let pattern = matches.value_of("pattern").unwrap_or("gen");
-        let cpp = matches.value_of("cpp-extension").unwrap_or("cc");
-        let desired_number = matches
-            .value_of("generate-exact")
-            .map(|s| s.parse::<usize>().unwrap());
-        let mut counter = 0usize;
-        for include_cxx in parsed_file.get_autocxxes() {
-            let generations = include_cxx
-                .generate_h_and_cxx()
-                .expect("Unable to generate header and C++ code");
-            for pair in generations.0 {
-                let cppname = format!("{}{}.{}", pattern, counter, cpp);
-                write_to_file(&outdir, cppname, &pair.implementation);
-                write_to_file(&outdir, pair.header_name, &pair.header);
-                counter += 1;
-            }
-        }
-        if let Some(desired_number) = desired_number {
-            while counter < desired_number {
-                write_cpp_file(
-                    &outdir,
-                    pattern,
-                    cpp,
-                    counter,
-                    "// Blank C++ file generated by autocxx".as_bytes(),
-                );
-                counter += 1;
-            }
-        }
+        generate_cpp(
+            &parsed_file,
+            matches,
+            &outdir,
+            &depfile,
+            input,
+            json_diagnostics,
+        );
+    } else if let Some(matches) = matches.subcommand_matches("build") {
+        let cc_files = generate_cpp(
+            &parsed_file,
+            matches,
+            &outdir,
+            &depfile,
+            input,
+            json_diagnostics,
+        );
+        let std = matches.value_of("std").unwrap_or("c++14");
+        let out_name = matches.value_of("out-name").unwrap_or("autocxxgen");
+        let inc_dirs: Vec<&str> = incs.split(',').filter(|s| !s.is_empty()).collect();
+        let toolchain =
+            Toolchain::discover().unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
+        toolchain
+            .compile_and_archive(&cc_files, &inc_dirs, std, &outdir, out_name)
+            .unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
     } else if matches.subcommand_matches("gen-rs").is_some() {
         let mut ts = TokenStream::new();
         parsed_file.to_tokens(&mut ts);
-        write_to_file(&outdir, "gen.rs".to_string(), ts.to_string().as_bytes());
+        let unformatted = ts.to_string();
+        let formatted = if matches.is_present("emit-unformatted") {
+            unformatted
+        } else {
+            rustfmt::format(&unformatted, matches.value_of("rustfmt"))
+        };
+        write_to_file(
+            &outdir,
+            "gen.rs".to_string(),
+            formatted.as_bytes(),
+            &depfile,
+        );
     } else {
         panic!("Must specify a subcommand");
     }
+    if let Some((depfile, path)) = depfile {
+        depfile
+            .borrow()
+            .write(&path)
+            .unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
+    }
+}
+
+/// Writes out the `.cc`/`.h` pairs for every `include_cpp!` in `parsed_file`,
+/// padding with blank files up to `--generate-exact` if requested, and
+/// returns the paths of the `.cc` implementation files that were written
+/// (used by the `build` subcommand to know what to compile).
+fn generate_cpp(
+    parsed_file: &autocxx_engine::ParsedFile,
+    matches: &clap::ArgMatches<'_>,
+    outdir: &PathBuf,
+    depfile: &Option<(Rc<RefCell<Depfile>>, PathBuf)>,
+    input: &str,
+    json_diagnostics: bool,
+) -> Vec<PathBuf> {
+    let pattern = matches.value_of("pattern").unwrap_or("gen");
+    let cpp = matches.value_of("cpp-extension").unwrap_or("cc");
+    let desired_number = matches
+        .value_of("generate-exact")
+        .map(|s| s.parse::<usize>().unwrap());
+    let mut counter = 0usize;
+    let mut cc_files = Vec::new();
+    for include_cxx in parsed_file.get_autocxxes() {
+        let generations = include_cxx
+            .generate_h_and_cxx()
+            .unwrap_or_else(|e| diagnostics::fail(input, e, json_diagnostics));
+        for pair in generations.0 {
+            let cppname = format!("{}{}.{}", pattern, counter, cpp);
+            cc_files.push(outdir.join(&cppname));
+            write_to_file(outdir, cppname, &pair.implementation, depfile);
+            write_to_file(outdir, pair.header_name, &pair.header, depfile);
+            counter += 1;
+        }
+    }
+    if let Some(desired_number) = desired_number {
+        while counter < desired_number {
+            let cppname = format!("{}{}.{}", pattern, counter, cpp);
+            cc_files.push(outdir.join(&cppname));
+            write_cpp_file(
+                outdir,
+                pattern,
+                cpp,
+                counter,
+                "// Blank C++ file generated by autocxx".as_bytes(),
+                depfile,
+            );
+            counter += 1;
+        }
+    }
+    cc_files
 }
 
-fn write_cpp_file(outdir: &PathBuf, pattern: &str, cpp: &str, counter: usize, content: &[u8]) {
+fn write_cpp_file(
+    outdir: &PathBuf,
+    pattern: &str,
+    cpp: &str,
+    counter: usize,
+    content: &[u8],
+    depfile: &Option<(Rc<RefCell<Depfile>>, PathBuf)>,
+) {
     let cppname = format!("{}{}.{}", pattern, counter, cpp);
-    write_to_file(outdir, cppname, content);
+    write_to_file(outdir, cppname, content, depfile);
 }
 
-fn write_to_file(dir: &PathBuf, filename: String, content: &[u8]) {
+fn write_to_file(
+    dir: &PathBuf,
+    filename: String,
+    content: &[u8],
+    depfile: &Option<(Rc<RefCell<Depfile>>, PathBuf)>,
+) {
     let path = dir.join(filename);
     let mut f = File::create(&path).expect("Unable to create file");
     f.write_all(content).expect("Unable to write file");
+    if let Some((depfile, _)) = depfile {
+        depfile.borrow_mut().add_target(path);
+    }
 }
@@ -0,0 +1,70 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pretty-prints the expanded Rust that `gen-rs` produces by piping it
+//! through `rustfmt`, the same way rust-analyzer's xtask locates and
+//! shells out to `rustfmt` for its own generated sources.
+
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Run `unformatted` through `rustfmt`, falling back to returning it
+/// unchanged if rustfmt can't be found or fails.
+pub(crate) fn format(unformatted: &str, rustfmt_path: Option<&str>) -> String {
+    run_rustfmt(unformatted, &locate_rustfmt(rustfmt_path))
+        .unwrap_or_else(|_| unformatted.to_string())
+}
+
+/// Find the `rustfmt` binary to use: an explicit `--rustfmt PATH`, then the
+/// `RUSTFMT` environment variable, then `rustfmt` on `PATH`.
+fn locate_rustfmt(rustfmt_path: Option<&str>) -> PathBuf {
+    if let Some(path) = rustfmt_path {
+        return PathBuf::from(path);
+    }
+    if let Ok(path) = env::var("RUSTFMT") {
+        return PathBuf::from(path);
+    }
+    PathBuf::from("rustfmt")
+}
+
+fn run_rustfmt(unformatted: &str, rustfmt: &PathBuf) -> io::Result<String> {
+    let mut child = Command::new(rustfmt)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("Child rustfmt process had no stdin");
+    // Write on a separate thread: rustfmt can start filling its stdout
+    // pipe before it has finished reading stdin, and if both pipes'
+    // buffers are full at once, writing stdin here while nothing is
+    // draining stdout would deadlock.
+    let unformatted = unformatted.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(unformatted.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .expect("rustfmt stdin writer thread panicked")?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "rustfmt exited with an error",
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
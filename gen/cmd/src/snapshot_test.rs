@@ -0,0 +1,276 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A golden-file regression harness for autocxx-gen, in the spirit of
+//! `compiletest_rs`: walk a directory of `.rs` fixtures, regenerate
+//! `.cc`/`.h`/`gen.rs` for each, and compare the result against files
+//! committed alongside the fixture. Use `--bless` to accept the current
+//! output as the new expectation.
+
+use crate::rustfmt;
+use autocxx_engine::parse_file;
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which generation modes a test run should cover.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModeFilter {
+    All,
+    GenCpp,
+    GenRs,
+}
+
+impl ModeFilter {
+    pub(crate) fn parse(s: &str) -> Self {
+        match s {
+            "gen-cpp" => ModeFilter::GenCpp,
+            "gen-rs" => ModeFilter::GenRs,
+            _ => ModeFilter::All,
+        }
+    }
+
+    fn wants_cpp(self) -> bool {
+        matches!(self, ModeFilter::All | ModeFilter::GenCpp)
+    }
+
+    fn wants_rs(self) -> bool {
+        matches!(self, ModeFilter::All | ModeFilter::GenRs)
+    }
+}
+
+pub(crate) struct Config {
+    pub(crate) fixtures_dir: PathBuf,
+    pub(crate) bless: bool,
+    pub(crate) mode: ModeFilter,
+}
+
+/// Runs every `.rs` fixture found directly inside `config.fixtures_dir`.
+/// Returns `true` if every fixture's output matched its `NAME.expected`
+/// directory (or was blessed).
+pub(crate) fn run(config: &Config) -> bool {
+    let mut all_passed = true;
+    for fixture in find_fixtures(&config.fixtures_dir) {
+        let name = fixture
+            .file_stem()
+            .expect("Fixture had no file name")
+            .to_string_lossy()
+            .into_owned();
+        let expected_dir = config.fixtures_dir.join(format!("{}.expected", name));
+        match generate(&fixture, config.mode) {
+            Ok(actual) if config.bless => {
+                bless(&expected_dir, &actual);
+                println!("blessed: {}", name);
+            }
+            Ok(actual) => {
+                if compare(&expected_dir, &actual, &name, config.mode) {
+                    println!("ok: {}", name);
+                } else {
+                    all_passed = false;
+                }
+            }
+            Err(e) => {
+                println!("FAILED: {} (could not generate: {})", name, e);
+                all_passed = false;
+            }
+        }
+    }
+    all_passed
+}
+
+fn find_fixtures(dir: &Path) -> Vec<PathBuf> {
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Unable to read fixtures dir {}: {}", dir.display(), e))
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "rs").unwrap_or(false))
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+/// Generates every output file for `fixture`, keyed by the filename it
+/// would be written as, with volatile content normalized away.
+fn generate(fixture: &Path, mode: ModeFilter) -> Result<Vec<(String, String)>, String> {
+    let mut parsed_file =
+        parse_file(fixture.to_str().expect("Non-UTF8 fixture path")).map_err(|e| e.to_string())?;
+    parsed_file
+        .resolve_all("", None)
+        .map_err(|e| e.to_string())?;
+    let mut outputs = Vec::new();
+    if mode.wants_cpp() {
+        let mut counter = 0usize;
+        for include_cxx in parsed_file.get_autocxxes() {
+            let generations = include_cxx
+                .generate_h_and_cxx()
+                .map_err(|e| e.to_string())?;
+            for pair in generations.0 {
+                outputs.push((
+                    format!("gen{}.cc", counter),
+                    normalize(&String::from_utf8_lossy(&pair.implementation)),
+                ));
+                outputs.push((
+                    pair.header_name.clone(),
+                    normalize(&String::from_utf8_lossy(&pair.header)),
+                ));
+                counter += 1;
+            }
+        }
+    }
+    if mode.wants_rs() {
+        let mut ts = TokenStream::new();
+        parsed_file.to_tokens(&mut ts);
+        let formatted = rustfmt::format(&ts.to_string(), None);
+        outputs.push(("gen.rs".to_string(), normalize(&formatted)));
+    }
+    Ok(outputs)
+}
+
+/// Strips content that varies across machines or runs (absolute include
+/// paths, the `genN` counter embedded in generated filenames) so that
+/// comparisons stay stable. Deliberately narrow: generated C++ is full of
+/// semantically meaningful integers (`sizeof` results, offsets, enum
+/// discriminants) that a real regression could change, so only the
+/// specific volatile tokens are touched, not every digit or every `/`.
+fn normalize(content: &str) -> String {
+    content
+        .lines()
+        .map(normalize_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn normalize_line(line: &str) -> String {
+    line.split(' ')
+        .map(|word| {
+            let bare = word.trim_matches('"');
+            if looks_like_path(bare) {
+                word.replace(bare, "<path>")
+            } else {
+                replace_gen_counter(word)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A path-shaped token: starts with `/` and has a further `/` later on, so
+/// that the `//` line-comment marker (and bare `//foo` comments) don't get
+/// mistaken for a path.
+fn looks_like_path(word: &str) -> bool {
+    word.starts_with('/') && word.len() > 1 && !word.starts_with("//") && word[1..].contains('/')
+}
+
+/// Rewrites the counter embedded in our own generated filenames, e.g.
+/// `gen3.cc` or `"gen12.h"` becomes `genN.cc`/`"genN.h"`, without touching
+/// unrelated digits elsewhere in the token.
+fn replace_gen_counter(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let is_gen = chars.get(i) == Some(&'g')
+            && chars.get(i + 1) == Some(&'e')
+            && chars.get(i + 2) == Some(&'n')
+            && chars
+                .get(i + 3)
+                .map(|c| c.is_ascii_digit())
+                .unwrap_or(false);
+        if is_gen {
+            result.push_str("gen");
+            let mut j = i + 3;
+            while chars.get(j).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                j += 1;
+            }
+            result.push('N');
+            i = j;
+        } else {
+            result.push(chars[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+fn bless(expected_dir: &Path, actual: &[(String, String)]) {
+    fs::create_dir_all(expected_dir)
+        .unwrap_or_else(|e| panic!("Unable to create {}: {}", expected_dir.display(), e));
+    for (filename, content) in actual {
+        fs::write(expected_dir.join(filename), content)
+            .unwrap_or_else(|e| panic!("Unable to write {}: {}", filename, e));
+    }
+}
+
+fn compare(
+    expected_dir: &Path,
+    actual: &[(String, String)],
+    name: &str,
+    mode: ModeFilter,
+) -> bool {
+    let mut all_matched = true;
+    let actual_names: std::collections::HashSet<&str> = actual
+        .iter()
+        .map(|(filename, _)| filename.as_str())
+        .collect();
+    for (filename, actual_content) in actual {
+        let expected_path = expected_dir.join(filename);
+        let expected_content = fs::read_to_string(&expected_path).unwrap_or_default();
+        if &expected_content != actual_content {
+            all_matched = false;
+            println!("FAILED: {}/{}", name, filename);
+            print_diff(&expected_content, actual_content);
+        }
+    }
+    if let Ok(entries) = fs::read_dir(expected_dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            let in_mode = if filename == "gen.rs" {
+                mode.wants_rs()
+            } else {
+                mode.wants_cpp()
+            };
+            if in_mode && !actual_names.contains(filename.as_str()) {
+                all_matched = false;
+                println!(
+                    "FAILED: {}/{} was expected but is no longer generated",
+                    name, filename
+                );
+            }
+        }
+    }
+    all_matched
+}
+
+/// Prints a minimal line diff by comparing the two files line by line at
+/// the same index: a differing pair is printed as `-expected` then
+/// `+actual`, and surplus lines of the longer file carry its prefix. Lines
+/// are not aligned, so one inserted or removed line makes every later line
+/// show as changed, and no hunk headers are printed.
+fn print_diff(expected: &str, actual: &str) {
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    for i in 0..expected_lines.len().max(actual_lines.len()) {
+        match (expected_lines.get(i), actual_lines.get(i)) {
+            (Some(e), Some(a)) if e == a => {}
+            (Some(e), Some(a)) => {
+                println!("-{}", e);
+                println!("+{}", a);
+            }
+            (Some(e), None) => println!("-{}", e),
+            (None, Some(a)) => println!("+{}", a),
+            (None, None) => {}
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A small, self-contained C++ compiler driver, so that `autocxx-gen build`
+//! can turn generated `.cc` files into a static library without requiring
+//! the caller to have their own build system. The compiler discovery here
+//! is deliberately modelled on the approach taken by the `cc` crate: prefer
+//! `CXX`/`CXXFLAGS` if the caller has set them, otherwise fall back to
+//! well-known compiler names for the host platform.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A C++ compiler invocation that has been resolved to a concrete binary,
+/// ready to have source files and flags appended to it.
+pub(crate) struct Toolchain {
+    compiler: PathBuf,
+    is_msvc: bool,
+    env: Vec<(OsString, OsString)>,
+}
+
+impl Toolchain {
+    /// Locate a C++ compiler for the host platform. Honors the `CXX`
+    /// environment variable if set; otherwise searches for `g++` or
+    /// `clang++` on Unix, or `cl.exe` (discovered via the registry) on
+    /// Windows.
+    pub(crate) fn discover() -> Result<Self, String> {
+        if let Ok(cxx) = env::var("CXX") {
+            let is_msvc = Path::new(&cxx)
+                .file_stem()
+                .map(|stem| stem.eq_ignore_ascii_case("cl"))
+                .unwrap_or(false);
+            return Ok(Toolchain {
+                compiler: PathBuf::from(cxx),
+                is_msvc,
+                env: Vec::new(),
+            });
+        }
+        if cfg!(windows) {
+            windows::find_msvc()
+        } else {
+            unix::find_gxx_or_clang()
+        }
+    }
+
+    /// Compile each of `sources` to an object file in `outdir`, honoring
+    /// `CXXFLAGS` and the given include directories and C++ standard, then
+    /// archive the resulting objects into a static library called
+    /// `lib{out_name}.a` (or `{out_name}.lib` for MSVC) in `outdir`.
+    pub(crate) fn compile_and_archive(
+        &self,
+        sources: &[PathBuf],
+        incs: &[&str],
+        std: &str,
+        outdir: &Path,
+        out_name: &str,
+    ) -> Result<PathBuf, String> {
+        let cxxflags = env::var("CXXFLAGS").unwrap_or_default();
+        let mut objects = Vec::new();
+        for source in sources {
+            let object = outdir.join(source.with_extension("o").file_name().unwrap());
+            let mut cmd = Command::new(&self.compiler);
+            for (key, value) in &self.env {
+                cmd.env(key, value);
+            }
+            if self.is_msvc {
+                cmd.arg(format!("/std:{}", std))
+                    .arg("/c")
+                    .arg(source)
+                    .arg(format!("/Fo{}", object.display()));
+            } else {
+                cmd.arg(format!("-std={}", std))
+                    .arg("-c")
+                    .arg(source)
+                    .arg("-o")
+                    .arg(&object);
+            }
+            for inc in incs {
+                cmd.arg(if self.is_msvc { "/I" } else { "-I" }).arg(inc);
+            }
+            if !cxxflags.is_empty() {
+                cmd.args(cxxflags.split_whitespace());
+            }
+            run(cmd)?;
+            objects.push(object);
+        }
+        self.archive(&objects, outdir, out_name)
+    }
+
+    fn archive(
+        &self,
+        objects: &[PathBuf],
+        outdir: &Path,
+        out_name: &str,
+    ) -> Result<PathBuf, String> {
+        if self.is_msvc {
+            let libname = outdir.join(format!("{}.lib", out_name));
+            let mut cmd = Command::new("lib.exe");
+            for (key, value) in &self.env {
+                cmd.env(key, value);
+            }
+            cmd.arg(format!("/OUT:{}", libname.display())).args(objects);
+            run(cmd)?;
+            Ok(libname)
+        } else {
+            let libname = outdir.join(format!("lib{}.a", out_name));
+            let mut cmd = Command::new("ar");
+            cmd.arg("rcs").arg(&libname).args(objects);
+            run(cmd)?;
+            Ok(libname)
+        }
+    }
+}
+
+fn run(mut cmd: Command) -> Result<(), String> {
+    let status = cmd
+        .status()
+        .map_err(|e| format!("Unable to run {:?}: {}", cmd, e))?;
+    if !status.success() {
+        return Err(format!("{:?} failed with {}", cmd, status));
+    }
+    Ok(())
+}
+
+mod unix {
+    use super::Toolchain;
+    use std::process::Command;
+
+    pub(super) fn find_gxx_or_clang() -> Result<Toolchain, String> {
+        for candidate in &["g++", "clang++"] {
+            if Command::new(candidate)
+                .arg("--version")
+                .output()
+                .map(|out| out.status.success())
+                .unwrap_or(false)
+            {
+                return Ok(Toolchain {
+                    compiler: candidate.into(),
+                    is_msvc: false,
+                    env: Vec::new(),
+                });
+            }
+        }
+        Err("Unable to find a C++ compiler; set the CXX environment variable".to_string())
+    }
+}
+
+mod windows {
+    use super::Toolchain;
+    use std::path::{Path, PathBuf};
+
+    /// A located Visual Studio installation: the `cl.exe` to invoke, and
+    /// the `vcvarsall.bat` alongside it that knows how to set up
+    /// `INCLUDE`/`LIB` for a given installation.
+    struct Installation {
+        cl: PathBuf,
+        vcvarsall: PathBuf,
+    }
+
+    /// Locate `cl.exe` by scanning the registry keys that Visual Studio
+    /// installs use, in roughly the same order as `vswhere`: newer
+    /// `SOFTWARE\Microsoft\VisualStudio` instance keys first, then the
+    /// legacy `SxS\VC7` key used by VS2015 and earlier. Then run
+    /// `vcvarsall.bat` for that installation to capture the `INCLUDE`/`LIB`
+    /// environment `cl.exe` needs to find the CRT and standard headers.
+    pub(super) fn find_msvc() -> Result<Toolchain, String> {
+        #[cfg(windows)]
+        {
+            use winreg::enums::HKEY_LOCAL_MACHINE;
+            use winreg::RegKey;
+
+            let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+            let installation = find_via_instances(&hklm).or_else(|| find_via_vc7(&hklm));
+            match installation {
+                Some(installation) => Ok(Toolchain {
+                    env: vcvars_env(&installation.vcvarsall)?,
+                    compiler: installation.cl,
+                    is_msvc: true,
+                }),
+                None => Err(
+                    "Unable to locate cl.exe via the registry; set the CXX environment variable"
+                        .to_string(),
+                ),
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            unreachable!("find_msvc is only called on Windows")
+        }
+    }
+
+    #[cfg(windows)]
+    fn find_via_instances(hklm: &winreg::RegKey) -> Option<Installation> {
+        let instances = hklm.open_subkey("SOFTWARE\\Microsoft\\VisualStudio").ok()?;
+        for name in instances.enum_keys().filter_map(Result::ok) {
+            if let Ok(instance) = instances.open_subkey(&name) {
+                if let Ok(install_dir) = instance.get_value::<String, _>("InstallDir") {
+                    if let Some(installation) = installation_from_install_dir(&install_dir) {
+                        return Some(installation);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    #[cfg(windows)]
+    fn find_via_vc7(hklm: &winreg::RegKey) -> Option<Installation> {
+        let vc7 = hklm
+            .open_subkey("SOFTWARE\\Microsoft\\VisualStudio\\SxS\\VC7")
+            .ok()?;
+        let mut versions: Vec<(String, String)> = vc7
+            .enum_values()
+            .filter_map(Result::ok)
+            .map(|(name, value)| (name, value.to_string()))
+            .collect();
+        versions.sort_by(|a, b| b.0.cmp(&a.0));
+        versions.into_iter().find_map(|(_, vc_dir)| {
+            let cl = PathBuf::from(&vc_dir).join("bin\\cl.exe");
+            let vcvarsall = PathBuf::from(&vc_dir).join("vcvarsall.bat");
+            (cl.exists() && vcvarsall.exists()).then(|| Installation { cl, vcvarsall })
+        })
+    }
+
+    /// `InstallDir` points at `<root>\Common7\IDE`; `cl.exe` and
+    /// `vcvarsall.bat` live under `<root>\VC\...`.
+    #[cfg(windows)]
+    fn installation_from_install_dir(install_dir: &str) -> Option<Installation> {
+        let root = Path::new(install_dir).ancestors().nth(2)?.to_path_buf();
+        let cl = root.join("VC\\Tools\\MSVC\\bin\\Hostx64\\x64\\cl.exe");
+        let vcvarsall = root.join("VC\\Auxiliary\\Build\\vcvarsall.bat");
+        (cl.exists() && vcvarsall.exists()).then(|| Installation { cl, vcvarsall })
+    }
+
+    /// Runs `vcvarsall.bat x64` and captures the environment variables it
+    /// sets, so that the `cl.exe`/`lib.exe` we invoke afterwards can find the
+    /// CRT and Windows SDK headers and libraries, and `lib.exe` itself can be
+    /// found on `PATH` if it isn't already.
+    #[cfg(windows)]
+    fn vcvars_env(
+        vcvarsall: &Path,
+    ) -> Result<Vec<(std::ffi::OsString, std::ffi::OsString)>, String> {
+        use std::process::Command;
+
+        let output = Command::new("cmd")
+            .arg("/c")
+            .arg(format!("\"{}\" x64 && set", vcvarsall.display()))
+            .output()
+            .map_err(|e| format!("Unable to run {}: {}", vcvarsall.display(), e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed with {}",
+                vcvarsall.display(),
+                output.status
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter(|(key, _)| matches!(*key, "INCLUDE" | "LIB" | "LIBPATH" | "PATH"))
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect())
+    }
+}